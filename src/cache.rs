@@ -0,0 +1,59 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::future::Future;
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+
+/// A small time-boxed cache for values that are expensive to fetch (e.g. over
+/// HTTP) but don't need to be fresher than `interval`.
+///
+/// A miss (key absent, or the cached entry is older than `interval`) awaits
+/// the caller-supplied `fetch` closure and atomically records the new value
+/// alongside the instant it was fetched; a hit just returns what's stored.
+pub struct AsyncCache<K, V> {
+    entries: HashMap<K, (Instant, V)>,
+    interval: Duration,
+}
+
+impl<K, V> AsyncCache<K, V>
+where
+    K: Eq + Hash,
+{
+    pub fn new(interval: Duration) -> Self {
+        AsyncCache {
+            entries: HashMap::new(),
+            interval,
+        }
+    }
+
+    fn is_stale(&self, key: &K) -> bool {
+        match self.entries.get(key) {
+            Some((last_update, _)) => Instant::now() - *last_update >= self.interval,
+            None => true,
+        }
+    }
+
+    /// Whether `get(key, ...)` would currently be served from cache rather
+    /// than triggering a fetch. Useful for cache hit/miss telemetry.
+    #[cfg_attr(not(feature = "stats"), allow(dead_code))]
+    pub fn is_fresh(&self, key: &K) -> bool {
+        !self.is_stale(key)
+    }
+
+    /// Returns the cached value for `key`, renewing it via `fetch` first if
+    /// it's missing or stale. The first call for a given key is always a
+    /// miss.
+    pub async fn get<F, Fut>(&mut self, key: K, fetch: F) -> Result<&V, Box<dyn Error>>
+    where
+        K: Clone,
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<V, Box<dyn Error>>>,
+    {
+        if self.is_stale(&key) {
+            let value = fetch().await?;
+            self.entries.insert(key.clone(), (Instant::now(), value));
+        }
+
+        Ok(&self.entries.get(&key).unwrap().1)
+    }
+}
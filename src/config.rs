@@ -0,0 +1,119 @@
+use serde::Deserialize;
+use std::error::Error;
+use std::fs;
+
+#[cfg(feature = "stats")]
+use crate::stats::StatsConfig;
+
+/// Path to the TOML config file, relative to the working directory the
+/// process is started from.
+pub const CONFIG_PATH: &str = "config.toml";
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Config {
+    /// Only required when the presence loop actually runs — `export` mode
+    /// never touches Discord, so it's fine for a Last.fm-only config to
+    /// leave this unset.
+    pub discord_client_id: Option<String>,
+    pub lastfm_api_key: String,
+    pub lastfm_username: String,
+
+    #[serde(default)]
+    pub presence: PresenceConfig,
+
+    #[cfg(feature = "stats")]
+    pub stats: Option<StatsConfig>,
+}
+
+/// Controls how the presence itself looks in Discord, on top of the
+/// credentials needed to populate it.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct PresenceConfig {
+    /// Discord activity type, e.g. `2` for Listening.
+    pub activity_type: u8,
+
+    pub small_image: String,
+    pub small_text: String,
+
+    pub button_label: String,
+    /// Where the button links to. Supports the same placeholders as the
+    /// format templates below, plus `{url}` for the track's Last.fm page.
+    /// Defaults to the track's Last.fm page.
+    pub button_target_format: String,
+
+    /// `details`/`state`/`large_text` templates. Support the placeholders
+    /// `{artist}`, `{album}` and `{track}`.
+    pub details_format: String,
+    pub state_format: String,
+    pub large_text_format: String,
+}
+
+impl Default for PresenceConfig {
+    fn default() -> Self {
+        PresenceConfig {
+            activity_type: 2,
+
+            small_image: "lastfm".into(),
+            small_text: "Last.fm".into(),
+
+            button_label: "Listen on Last.fm".into(),
+            button_target_format: "{url}".into(),
+
+            details_format: "{track}".into(),
+            state_format: "by {artist}".into(),
+            large_text_format: "on {album}".into(),
+        }
+    }
+}
+
+impl Config {
+    pub fn load(path: &str) -> Result<Config, Box<dyn Error>> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read config file '{}': {}", path, e))?;
+
+        let config: Config = toml::from_str(&contents)
+            .map_err(|e| format!("Failed to parse config file '{}': {}", path, e))?;
+
+        Ok(config)
+    }
+}
+
+/// Substitutes `{artist}`, `{album}`, `{track}` and `{url}` placeholders in
+/// a presence format template.
+///
+/// This scans `template` once rather than chaining `.replace()` calls, so a
+/// substituted value that happens to contain a literal `{track}`-style
+/// substring (not unheard of in real track titles) can't be corrupted by a
+/// later substitution pass.
+pub fn format_template(template: &str, artist: &str, album: &str, track: &str, url: &str) -> String {
+    let mut result = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        let Some(end) = rest[start..].find('}') else {
+            result.push_str(rest);
+            return result;
+        };
+        let end = start + end;
+
+        result.push_str(&rest[..start]);
+
+        match &rest[start + 1..end] {
+            "artist" => result.push_str(artist),
+            "album" => result.push_str(album),
+            "track" => result.push_str(track),
+            "url" => result.push_str(url),
+            other => {
+                result.push('{');
+                result.push_str(other);
+                result.push('}');
+            }
+        }
+
+        rest = &rest[end + 1..];
+    }
+
+    result.push_str(rest);
+    result
+}
@@ -0,0 +1,186 @@
+use crate::config::Config;
+use crate::Track;
+use reqwest::Url;
+use serde_json::Value;
+use std::error::Error;
+
+/// Paginates through a user's entire `user.getrecenttracks` history,
+/// newest-first, stopping once Last.fm runs out of pages or (if set) once
+/// `from` is reached.
+pub struct ScrobbleExporter {
+    api_key: String,
+    username: String,
+    from: Option<i64>,
+
+    page: u32,
+    total_pages: Option<u32>,
+    exhausted: bool,
+}
+
+impl ScrobbleExporter {
+    pub fn new(api_key: String, username: String, from: Option<i64>) -> Self {
+        ScrobbleExporter {
+            api_key,
+            username,
+            from,
+
+            page: 0,
+            total_pages: None,
+            exhausted: false,
+        }
+    }
+
+    /// Fetches the next page of tracks, or `None` once history is
+    /// exhausted.
+    pub async fn next_page(&mut self) -> Result<Option<Vec<Track>>, Box<dyn Error>> {
+        if self.exhausted {
+            return Ok(None);
+        }
+
+        if let Some(total_pages) = self.total_pages {
+            if self.page >= total_pages {
+                self.exhausted = true;
+                return Ok(None);
+            }
+        }
+
+        self.page += 1;
+
+        let mut url_query = vec![
+            ("method", "user.getrecenttracks".to_string()),
+            ("user", self.username.clone()),
+            ("format", "json".to_string()),
+            ("extended", "1".to_string()),
+            ("api_key", self.api_key.clone()),
+            ("limit", "200".to_string()),
+            ("page", self.page.to_string()),
+        ];
+
+        if let Some(from) = self.from {
+            url_query.push(("from", from.to_string()));
+        }
+
+        let url = Url::parse_with_params("https://ws.audioscrobbler.com/2.0/", &url_query)?;
+
+        let body = reqwest::get(url).await?.text().await?;
+        let json: Value = serde_json::from_str(&body)?;
+
+        let recenttracks = json
+            .as_object()
+            .ok_or("Failed to cast response as JSON object.")?
+            .get("recenttracks")
+            .ok_or("Failed to get recenttracks key.")?;
+
+        let total_pages: u32 = recenttracks
+            .get("@attr")
+            .and_then(|attr| attr.get("totalPages"))
+            .and_then(|v| v.as_str())
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(1);
+        self.total_pages = Some(total_pages);
+
+        let tracks = recenttracks
+            .get("track")
+            .ok_or("Failed to get track key.")?
+            .as_array()
+            .ok_or("Failed to cast track key as array.")?;
+
+        let tracks: Vec<Track> = serde_json::from_value(Value::Array(tracks.clone()))?;
+
+        if tracks.is_empty() || self.page >= total_pages {
+            self.exhausted = true;
+        }
+
+        if tracks.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(tracks))
+    }
+}
+
+fn csv_escape(field: &str) -> String {
+    if field.contains(',') || field.contains('"') || field.contains('\n') {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn track_to_csv_row(track: &Track) -> String {
+    format!(
+        "{},{},{},{},{},{}",
+        track.date.to_rfc3339(),
+        csv_escape(&track.artist.name),
+        csv_escape(&track.album.name),
+        csv_escape(&track.name),
+        csv_escape(&track.url),
+        track.now_playing,
+    )
+}
+
+/// Runs the `export` subcommand: `lastfm-rp export [--format json|csv] [--from <unix timestamp>]`.
+///
+/// Pages are written out to stdout as soon as they're fetched instead of
+/// buffering the whole (potentially huge) scrobble history in memory first.
+pub async fn run(config: &Config, args: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut format = "json".to_string();
+    let mut from: Option<i64> = None;
+
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--format" => {
+                format = args.get(i + 1).ok_or("--format expects a value")?.clone();
+                i += 1;
+            },
+            "--from" => {
+                from = Some(args.get(i + 1).ok_or("--from expects a value")?.parse()?);
+                i += 1;
+            },
+            other => return Err(format!("Unrecognised export argument: {}", other).into()),
+        }
+
+        i += 1;
+    }
+
+    if format != "json" && format != "csv" {
+        return Err(format!("Unsupported export format: {}", format).into());
+    }
+
+    let mut exporter = ScrobbleExporter::new(config.lastfm_api_key.clone(), config.lastfm_username.clone(), from);
+
+    let mut total = 0usize;
+    let mut first = true;
+
+    if format == "json" {
+        print!("[");
+    } else {
+        println!("date,artist,album,track,url,now_playing");
+    }
+
+    while let Some(page) = exporter.next_page().await? {
+        for track in &page {
+            total += 1;
+
+            if format == "json" {
+                if !first {
+                    print!(",");
+                }
+                print!("\n{}", serde_json::to_string_pretty(track)?);
+            } else {
+                println!("{}", track_to_csv_row(track));
+            }
+
+            first = false;
+        }
+
+        eprintln!("Export: Fetched {} scrobbles so far...", total);
+    }
+
+    if format == "json" {
+        println!("\n]");
+    }
+
+    Ok(())
+}
@@ -1,6 +1,5 @@
 use chrono::{DateTime, TimeZone, Utc};
 use discord_rich_presence::activity::Timestamps;
-use dotenv::dotenv;
 use uuid::Uuid;
 use std::{error::Error, time::Instant};
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
@@ -10,11 +9,25 @@ use reqwest::Url;
 use serde::{Deserialize, Deserializer, Serialize};
 use serde::de::Error as _LFMError;
 
-fn create_discord_client() -> Result<DiscordIpcClient, Box<dyn Error>> {
-    let client_id = std::env::var("DISCORD_CLIENT_ID")
-        .expect("Missing DISCORD_CLIENT_ID env variable");
+mod cache;
+use cache::AsyncCache;
 
-    let mut discord = DiscordIpcClient::new(&client_id)
+mod config;
+use config::{format_template, Config, CONFIG_PATH};
+
+#[cfg(feature = "stats")]
+mod stats;
+#[cfg(feature = "stats")]
+use stats::Stats;
+
+mod export;
+
+/// How long a fetched `user.getrecenttracks` page is trusted for before the
+/// cache will hit Last.fm again.
+const RECENT_TRACKS_CACHE_INTERVAL: Duration = Duration::from_secs(10);
+
+fn create_discord_client(client_id: &str) -> Result<DiscordIpcClient, Box<dyn Error>> {
+    let mut discord = DiscordIpcClient::new(client_id)
         .expect("Failed to create Discord RPC client");
 
     discord.connect()
@@ -127,6 +140,44 @@ impl LFMImageSet {
     }
 }
 
+#[derive(Debug, Clone, Default)]
+struct TrackInfo {
+    duration: Option<i64>,
+    userplaycount: Option<u64>,
+    tag: Option<String>,
+}
+
+/// Picks the three extended fields we care about out of a raw
+/// `track.getInfo` "track" object field by field, rather than deserializing
+/// it into one strict struct. Last.fm's `toptags.tag` famously collapses to
+/// a bare object instead of a one-element array when there's only one tag,
+/// among other shape quirks — a single malformed field shouldn't cost us
+/// the already-reliable `duration`/`userplaycount` alongside it.
+fn parse_track_info(track: &Value) -> TrackInfo {
+    // track.getInfo reports a "0" duration when Last.fm doesn't know it, so
+    // treat that the same as an absent field.
+    let duration = track.get("duration")
+        .and_then(Value::as_str)
+        .and_then(|s| s.parse::<i64>().ok())
+        .filter(|ms| *ms > 0);
+
+    let userplaycount = track.get("userplaycount")
+        .and_then(Value::as_str)
+        .and_then(|s| s.parse::<u64>().ok());
+
+    let tag = track.get("toptags")
+        .and_then(|toptags| toptags.get("tag"))
+        .and_then(|tag| match tag.as_array() {
+            Some(tags) => tags.first(),
+            None => Some(tag),
+        })
+        .and_then(|tag| tag.get("name"))
+        .and_then(Value::as_str)
+        .map(str::to_string);
+
+    TrackInfo { duration, userplaycount, tag }
+}
+
 fn unix_to_datetime<'de, D>(deserializer: D) -> Result<DateTime<Utc>, D::Error>
 where
     D: Deserializer<'de>,
@@ -162,7 +213,7 @@ where
         .unwrap_or("false") == "true")
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 struct Track {
     #[serde(rename = "@attr", default, deserialize_with = "attr_now_playing")]
     now_playing: bool,
@@ -170,6 +221,9 @@ struct Track {
     #[serde(default, deserialize_with = "str_bool_to_real_bool")]
     streamable: bool,
 
+    #[serde(default, deserialize_with = "str_bool_to_real_bool")]
+    loved: bool,
+
     mbid: String,
 
     name: String,
@@ -185,14 +239,38 @@ struct Track {
     image: LFMImageSet
 }
 
+impl Track {
+    /// Whether `other` is the same play as `self`, ignoring fields that can
+    /// legitimately change mid-playback (e.g. toggling "loved" on Last.fm).
+    /// Used to decide whether a new `user.getrecenttracks` poll represents a
+    /// track change, so those fields must never factor in here.
+    fn is_same_play(&self, other: &Track) -> bool {
+        self.mbid == other.mbid
+            && self.name == other.name
+            && self.url == other.url
+            && self.date == other.date
+            && self.artist == other.artist
+            && self.album == other.album
+    }
+}
+
 struct Application {
     discord: DiscordIpcClient,
+    config: Config,
 
     current_track: Option<Track>,
     current_track_started: SystemTime,
+    current_track_duration: Option<i64>,
+    current_track_playcount: Option<u64>,
+    current_track_tag: Option<String>,
 
     timer_active: bool,
     timer_started: Instant,
+
+    recent_tracks_cache: AsyncCache<String, Vec<Track>>,
+
+    #[cfg(feature = "stats")]
+    stats: Stats,
 }
 impl Application {
     async fn process_loop(&mut self) {
@@ -201,6 +279,9 @@ impl Application {
                 Ok(_) => {},
                 Err(e) => {
                     eprintln!("Last.fm: {}", e);
+
+                    #[cfg(feature = "stats")]
+                    self.stats.record_api_error();
                 }
             }
 
@@ -209,9 +290,21 @@ impl Application {
                 self.timer_started = Instant::now();
 
                 if let Some(track) = &self.current_track {
-                    let state = format!("by {}", track.artist.name.clone());
-                    let details = track.name.clone();
-                    let status_text = format!("on {}", track.album.name.clone());
+                    let presence = &self.config.presence;
+
+                    let details = format_template(&presence.details_format, &track.artist.name, &track.album.name, &track.name, &track.url);
+
+                    let mut state = format_template(&presence.state_format, &track.artist.name, &track.album.name, &track.name, &track.url);
+                    if track.loved {
+                        state = format!("♥ {}", state);
+                    }
+
+                    let mut status_text = format_template(&presence.large_text_format, &track.artist.name, &track.album.name, &track.name, &track.url);
+                    if let Some(playcount) = self.current_track_playcount {
+                        status_text = format!("{} · ♥ {} plays", status_text, playcount);
+                    }
+
+                    let small_text = self.current_track_tag.clone().unwrap_or_else(|| presence.small_text.clone());
 
                     let album_art = track.image.to_vec();
                     let album_art_url = album_art.last().unwrap_or(&"blank_art");
@@ -219,14 +312,19 @@ impl Application {
                     let assets = Assets::new()
                         .large_image(album_art_url)
                         .large_text(&status_text)
-                        .small_image("lastfm")
-                        .small_text("Last.fm");
+                        .small_image(&presence.small_image)
+                        .small_text(&small_text);
 
                     let track_started = self.current_track_started.duration_since(UNIX_EPOCH).unwrap().as_millis() as i64;
-                    let timestamps = Timestamps::new()
+                    let mut timestamps = Timestamps::new()
                         .start(track_started);
+
+                    if let Some(duration_ms) = self.current_track_duration {
+                        timestamps = timestamps.end(track_started + duration_ms);
+                    }
+                    let button_target = format_template(&presence.button_target_format, &track.artist.name, &track.album.name, &track.name, &track.url);
                     let buttons = vec![
-                        Button::new("Listen on Last.fm", &track.url)
+                        Button::new(&presence.button_label, &button_target)
                     ];
 
                     println!("Discord: Updating activity with:\n{:#?}", track);
@@ -245,7 +343,7 @@ impl Application {
                         .as_object_mut()
                         .unwrap();
 
-                    activity_json_mut.insert("type".into(), 2.into());
+                    activity_json_mut.insert("type".into(), presence.activity_type.into());
 
                     let data = json!({
                         "cmd": "SET_ACTIVITY",
@@ -257,6 +355,17 @@ impl Application {
                     });
 
                     self.discord.send(data, 1).unwrap();
+
+                    #[cfg(feature = "stats")]
+                    {
+                        self.stats.record_tracks_played();
+
+                        if let Some(stats_config) = &self.config.stats {
+                            if let Err(e) = self.stats.push(stats_config).await {
+                                eprintln!("Stats: Failed to push metrics: {}", e);
+                            }
+                        }
+                    }
                 } else {
                     println!("Discord: Playback stopped, clearing activity.");
 
@@ -270,18 +379,65 @@ impl Application {
     }
 
     async fn update_current_activity(&mut self) -> Result<(), Box<dyn Error>>  {
-        let api_key = std::env::var("LASTFM_API_KEY")
-            .expect("Missing LASTFM_API_KEY env variable");
+        let api_key = self.config.lastfm_api_key.clone();
+        let username = self.config.lastfm_username.clone();
+
+        #[cfg(feature = "stats")]
+        {
+            if self.recent_tracks_cache.is_fresh(&username) {
+                self.stats.record_cache_hit();
+            } else {
+                self.stats.record_cache_miss();
+            }
+        }
+
+        let latest_tracks = self.recent_tracks_cache
+            .get(username.clone(), || Self::fetch_recent_tracks(api_key.clone(), username))
+            .await?;
+
+        let now_playing = latest_tracks
+            .iter()
+            .find(|x|  x.now_playing);
+
+        match now_playing {
+            Some(track) => {
+                // Check if the current track is the same as the new one
+                if !self.current_track.as_ref().is_some_and(|current| current.is_same_play(track)) {
+                    println!("Last.fm: Updating track information to: {:#?}", track);
 
-        let username = std::env::var("LASTFM_USERNAME")
-            .expect("Missing LASTFM_USERNAME env variable");
+                    self.timer_active = false;
+                    self.current_track = Some(track.clone());
+                    self.current_track_started = SystemTime::now();
 
+                    #[cfg(feature = "stats")]
+                    self.stats.record_track_change();
+
+                    // track.getInfo is unreliable, so a failed/empty lookup
+                    // just means a plainer presence rather than a hard error.
+                    let track_info = Self::fetch_track_info(&self.config.lastfm_api_key, &self.config.lastfm_username, track)
+                        .await
+                        .unwrap_or(None);
+
+                    self.current_track_duration = track_info.as_ref().and_then(|info| info.duration);
+                    self.current_track_playcount = track_info.as_ref().and_then(|info| info.userplaycount);
+                    self.current_track_tag = track_info.and_then(|info| info.tag);
+                }
+            },
+            _ => {
+                self.current_track = None;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn fetch_recent_tracks(api_key: String, username: String) -> Result<Vec<Track>, Box<dyn Error>> {
         let url_query = vec![
             ("method", "user.getrecenttracks".to_string()),
             ("user", username),
             ("format", "json".to_string()),
             ("extended", "1".to_string()),
-            ("api_key", api_key.to_string()),
+            ("api_key", api_key),
             ("limit", "1".to_string()),
         ];
 
@@ -308,54 +464,134 @@ impl Application {
             .as_array()
             .ok_or("Failed to cast track key as array.")?;
 
-        let latest_tracks: Vec<Track> = serde_json::from_value(Value::Array(latest_tracks.clone()))?;
+        Ok(serde_json::from_value(Value::Array(latest_tracks.clone()))?)
+    }
 
-        let now_playing = latest_tracks
-            .iter()
-            .find(|x|  x.now_playing);
+    async fn fetch_track_info(api_key: &str, username: &str, track: &Track) -> Result<Option<TrackInfo>, Box<dyn Error>> {
+        let url_query = vec![
+            ("method", "track.getInfo".to_string()),
+            ("mbid", track.mbid.clone()),
+            ("artist", track.artist.name.clone()),
+            ("track", track.name.clone()),
+            ("username", username.to_string()),
+            ("format", "json".to_string()),
+            ("api_key", api_key.to_string()),
+        ];
 
-        match now_playing {
-            Some(track) => {
-                // Check if the current track is the same as the new one
-                if self.current_track.is_none() || &self.current_track.clone().unwrap() != track {
-                    println!("Last.fm: Updating track information to: {:#?}", track);
+        let url = Url::parse_with_params("https://ws.audioscrobbler.com/2.0/", &url_query)
+            .unwrap();
 
-                    self.timer_active = false;
-                    self.current_track = Some(track.clone());
-                    self.current_track_started = SystemTime::now();
-                }
-            },
-            _ => {
-                self.current_track = None;
-            }
-        }
+        let body = reqwest::get(url)
+            .await?
+            .text()
+            .await?;
 
-        Ok(())
+        let json: Value = serde_json::from_str(&body)?;
+
+        let track = json.as_object()
+            .ok_or("Failed to cast response as JSON object.")?
+            .get("track")
+            .ok_or("Failed to get track key.")?;
+
+        Ok(Some(parse_track_info(track)))
     }
 
-    fn new() -> Application {
-        let discord = create_discord_client()
+    fn new(config: Config) -> Application {
+        let client_id = config.discord_client_id
+            .clone()
+            .expect("Missing discord_client_id in config.toml");
+
+        let discord = create_discord_client(&client_id)
             .expect("Failed to create Discord RPC client");
 
         Application {
             discord,
+            config,
 
             current_track: None,
             current_track_started: SystemTime::now(),
+            current_track_duration: None,
+            current_track_playcount: None,
+            current_track_tag: None,
 
             timer_active: false,
-            timer_started: Instant::now()
+            timer_started: Instant::now(),
+
+            recent_tracks_cache: AsyncCache::new(RECENT_TRACKS_CACHE_INTERVAL),
+
+            #[cfg(feature = "stats")]
+            stats: Stats::new(),
         }
     }
 }
 
 #[tokio::main]
 async fn main() {
-    dotenv().ok().expect("Failed to load .env");
+    let config = Config::load(CONFIG_PATH)
+        .expect("Failed to load config.toml");
 
-    let mut app = Application::new();
+    let args: Vec<String> = std::env::args().collect();
+
+    if args.get(1).map(String::as_str) == Some("export") {
+        export::run(&config, &args[2..])
+            .await
+            .expect("Failed to export scrobble history");
+
+        return;
+    }
+
+    let mut app = Application::new(config);
 
     app.process_loop().await;
 
     app.discord.close().unwrap();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_track_info_reads_single_toptag_as_bare_object() {
+        let track = json!({
+            "duration": "210000",
+            "userplaycount": "7",
+            "toptags": {
+                "tag": { "name": "shoegaze", "url": "https://last.fm/tag/shoegaze" }
+            }
+        });
+
+        let info = parse_track_info(&track);
+
+        assert_eq!(info.duration, Some(210000));
+        assert_eq!(info.userplaycount, Some(7));
+        assert_eq!(info.tag, Some("shoegaze".to_string()));
+    }
+
+    #[test]
+    fn parse_track_info_reads_first_of_multiple_toptags_as_array() {
+        let track = json!({
+            "duration": "180000",
+            "userplaycount": "3",
+            "toptags": {
+                "tag": [
+                    { "name": "dream pop", "url": "https://last.fm/tag/dream-pop" },
+                    { "name": "shoegaze", "url": "https://last.fm/tag/shoegaze" }
+                ]
+            }
+        });
+
+        let info = parse_track_info(&track);
+
+        assert_eq!(info.tag, Some("dream pop".to_string()));
+    }
+
+    #[test]
+    fn parse_track_info_treats_zero_duration_as_unknown() {
+        let track = json!({ "duration": "0" });
+
+        let info = parse_track_info(&track);
+
+        assert_eq!(info.duration, None);
+    }
+}
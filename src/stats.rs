@@ -0,0 +1,99 @@
+#![cfg(feature = "stats")]
+
+use serde::Deserialize;
+use std::error::Error;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Where to push accumulated counters to.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "lowercase")]
+pub enum StatsBackend {
+    Redis,
+    Pushgateway,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct StatsConfig {
+    pub backend: StatsBackend,
+    pub endpoint: String,
+}
+
+/// In-memory counters for what the app is doing, pushed out to `backend` on
+/// every activity update. Counting is lock-free so it can be touched from
+/// the hot presence loop without contention.
+#[derive(Default)]
+pub struct Stats {
+    tracks_played: AtomicU64,
+    track_changes: AtomicU64,
+    api_errors: AtomicU64,
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Stats::default()
+    }
+
+    pub fn record_tracks_played(&self) {
+        self.tracks_played.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_track_change(&self) {
+        self.track_changes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_api_error(&self) {
+        self.api_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_cache_hit(&self) {
+        self.cache_hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_cache_miss(&self) {
+        self.cache_misses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub async fn push(&self, config: &StatsConfig) -> Result<(), Box<dyn Error>> {
+        match config.backend {
+            StatsBackend::Redis => self.push_redis(&config.endpoint).await,
+            StatsBackend::Pushgateway => self.push_pushgateway(&config.endpoint).await,
+        }
+    }
+
+    async fn push_redis(&self, endpoint: &str) -> Result<(), Box<dyn Error>> {
+        let client = redis::Client::open(endpoint)?;
+        let mut conn = client.get_multiplexed_async_connection().await?;
+
+        redis::pipe()
+            .set("lastfm_rp_tracks_played", self.tracks_played.load(Ordering::Relaxed))
+            .set("lastfm_rp_track_changes", self.track_changes.load(Ordering::Relaxed))
+            .set("lastfm_rp_api_errors", self.api_errors.load(Ordering::Relaxed))
+            .set("lastfm_rp_cache_hits", self.cache_hits.load(Ordering::Relaxed))
+            .set("lastfm_rp_cache_misses", self.cache_misses.load(Ordering::Relaxed))
+            .query_async::<()>(&mut conn)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn push_pushgateway(&self, endpoint: &str) -> Result<(), Box<dyn Error>> {
+        let body = format!(
+            "lastfm_rp_tracks_played {}\nlastfm_rp_track_changes {}\nlastfm_rp_api_errors {}\nlastfm_rp_cache_hits {}\nlastfm_rp_cache_misses {}\n",
+            self.tracks_played.load(Ordering::Relaxed),
+            self.track_changes.load(Ordering::Relaxed),
+            self.api_errors.load(Ordering::Relaxed),
+            self.cache_hits.load(Ordering::Relaxed),
+            self.cache_misses.load(Ordering::Relaxed),
+        );
+
+        reqwest::Client::new()
+            .post(format!("{}/metrics/job/lastfm_rp", endpoint))
+            .body(body)
+            .send()
+            .await?;
+
+        Ok(())
+    }
+}